@@ -0,0 +1,969 @@
+//! Library support for the `crunch` CLI: remote configuration, plan computation, and the
+//! transfer/build/copy-back/cleanup stages that move a build onto a remote machine and back.
+//! Kept separate from `main.rs` so integration tests can drive these stages directly against a
+//! real (or containerized) remote without going through the CLI.
+
+use cargo_metadata::camino::Utf8PathBuf;
+use cargo_metadata::Message;
+use clap::ValueEnum;
+use log::{debug, error, info};
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    io::BufReader,
+    process::{Command, Stdio},
+    sync::{Arc, Mutex},
+    thread,
+    time::SystemTime,
+};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Remote {
+    pub name: String,
+    pub host: String,
+    #[serde(default = "default_ssh_port")]
+    pub ssh_port: u16,
+    #[serde(default = "default_temp_dir")]
+    pub temp_dir: String,
+    #[serde(default = "default_env_profile")]
+    pub env: String,
+    /// Target triple this remote is best suited to build for. Used by `--remote auto` to match
+    /// a requested `--target` to a remote that advertises it.
+    #[serde(default)]
+    pub target: Option<String>,
+}
+
+pub fn default_ssh_port() -> u16 {
+    22
+}
+
+pub fn default_temp_dir() -> String {
+    "~/crunch-builds".to_string()
+}
+
+pub fn default_env_profile() -> String {
+    "~/.profile".to_string()
+}
+
+/// The zero-config remote crunch has always shipped with, used when no `crunch.toml`/
+/// `~/.config/crunch/config.toml` is found.
+pub fn default_remote() -> Remote {
+    Remote {
+        name: "crunch".to_string(),
+        host: "crunch".to_string(),
+        ssh_port: default_ssh_port(),
+        temp_dir: default_temp_dir(),
+        env: default_env_profile(),
+        target: None,
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Config {
+    #[serde(default, rename = "remote")]
+    remotes: Vec<Remote>,
+}
+
+/// Load remote definitions from `crunch.toml` in the current directory, falling back to
+/// `~/.config/crunch/config.toml`, falling back to the single built-in [`default_remote`].
+pub fn load_config() -> Vec<Remote> {
+    let mut search_paths = vec!["crunch.toml".to_string()];
+    if let Ok(home) = std::env::var("HOME") {
+        search_paths.push(format!("{}/.config/crunch/config.toml", home));
+    }
+
+    for path in &search_paths {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(_) => continue,
+        };
+        let config: Config = toml::from_str(&contents).unwrap_or_else(|e| {
+            error!("Failed to parse config file '{}' (error: {})", path, e);
+            std::process::exit(-7);
+        });
+        debug!("Loaded remote configuration from {}", path);
+        if config.remotes.is_empty() {
+            return vec![default_remote()];
+        }
+        return config.remotes;
+    }
+
+    vec![default_remote()]
+}
+
+/// Select a remote by `--remote <name>`, defaulting to the first configured remote. `--remote
+/// auto` matches a remote advertising the requested `--target` triple, falling back to
+/// round-robin hashing of the workspace root so concurrent projects spread across machines.
+pub fn select_remote<'a>(
+    remotes: &'a [Remote],
+    selector: Option<&str>,
+    target: Option<&str>,
+    workspace_root: &Utf8PathBuf,
+) -> &'a Remote {
+    match selector {
+        None => &remotes[0],
+        Some("auto") => {
+            if let Some(target) = target {
+                if let Some(matched) = remotes.iter().find(|r| r.target.as_deref() == Some(target))
+                {
+                    return matched;
+                }
+            }
+            let idx = (uid_from_path(workspace_root) as usize) % remotes.len();
+            &remotes[idx]
+        }
+        Some(name) => remotes.iter().find(|r| r.name == name).unwrap_or_else(|| {
+            error!("No remote named '{}' configured", name);
+            std::process::exit(-3);
+        }),
+    }
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum RemotePathBehavior {
+    /// Mirror the local directory structure on the remote server (default)
+    Mirror,
+    /// Use a temporary directory on the remote server that cleans up afterwards
+    Tmp,
+    /// Use a unique persistent directory in the user's home directory for each project
+    Unique,
+}
+
+pub fn uid_from_path(path: &Utf8PathBuf) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    path.as_str().hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Resolve the remote build directory for a given [`RemotePathBehavior`]. `Unique`'s persistent
+/// directory lives under the selected remote's configured `temp_dir` rather than a hardcoded
+/// path, so a `crunch.toml` remote that sets a custom `temp_dir` actually takes effect.
+pub fn resolve_build_path(
+    behavior: &RemotePathBehavior,
+    remote: &Remote,
+    project_dir: &Utf8PathBuf,
+) -> String {
+    match behavior {
+        RemotePathBehavior::Tmp => {
+            // Generate UID locally to avoid RTT latency
+            let project_name = project_dir
+                .file_name()
+                .expect("Project dir should always exist");
+            let uid = SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos();
+            let temp_path = format!("/tmp/crunch-{}-{}", project_name, uid);
+            info!("Using temporary directory: {}", temp_path);
+            temp_path
+        }
+        RemotePathBehavior::Unique => {
+            let project_name = project_dir
+                .file_name()
+                .expect("Project dir should always exist");
+            let uid = uid_from_path(project_dir);
+            let unique_path = format!("{}/{}-{}", remote.temp_dir, project_name, uid);
+
+            debug!("Using unique persistent directory: {}", unique_path);
+            unique_path
+        }
+        RemotePathBehavior::Mirror => project_dir.to_string(),
+    }
+}
+
+/// Build the argv (without the `rsync` binary itself) that mirrors `project_dir` onto
+/// `remote:build_path`, always excluding `target` plus any caller-supplied globs (`.git` is
+/// excluded only when the caller passes it, as the CLI's default `--exclude` value does).
+pub fn build_rsync_to_argv(
+    remote: &Remote,
+    project_dir: &Utf8PathBuf,
+    build_path: &str,
+    exclude: &[String],
+) -> Vec<String> {
+    let mut argv: Vec<String> = vec![
+        "-a".to_string(),
+        "--delete".to_string(),
+        "--compress".to_string(),
+        "-e".to_string(),
+        format!("ssh -p {}", remote.ssh_port),
+        "--info=progress2".to_string(),
+        "--exclude".to_string(),
+        "target".to_string(),
+    ];
+    exclude.iter().for_each(|entry| {
+        argv.push("--exclude".to_string());
+        argv.push(entry.clone());
+    });
+    argv.push("--rsync-path".to_string());
+    argv.push(format!("mkdir -p {} && rsync", build_path));
+    argv.push(format!("{}/", project_dir));
+    argv.push(format!("{}:{}", remote.host, build_path));
+    argv
+}
+
+/// Transfer stage: rsync the project sources onto the remote using a previously built argv
+/// (see [`build_rsync_to_argv`]).
+pub fn transfer_sources(rsync_to_argv: &[String]) -> Result<(), String> {
+    let output = Command::new("rsync")
+        .args(rsync_to_argv)
+        .env("LC_ALL", "C.UTF-8")
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .stdin(Stdio::inherit())
+        .output()
+        .map_err(|e| format!("Failed to transfer project to build server (error: {})", e))?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "Transfer to build server exited with status: {}",
+            output.status
+        ));
+    }
+    Ok(())
+}
+
+/// Build stage: run `command` on the remote over an interactive ssh session, inheriting
+/// stdio so the user sees cargo's output live.
+pub fn run_build(remote: &Remote, command: &str) -> Result<(), String> {
+    let status = Command::new("ssh")
+        .env("LC_ALL", "C.UTF-8")
+        .args(&["-p", &remote.ssh_port.to_string()])
+        .arg("-t")
+        .arg(&remote.host)
+        .arg(command)
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .stdin(Stdio::inherit())
+        .status()
+        .map_err(|e| format!("Failed to run cargo command remotely (error: {})", e))?;
+
+    if !status.success() {
+        return Err(format!("Remote cargo command exited with status: {}", status));
+    }
+    Ok(())
+}
+
+/// Build stage variant for artifact discovery: run `command` over ssh with its stdout piped so
+/// the `--message-format=json-render-diagnostics` stream can be parsed, printing rendered
+/// diagnostics as they arrive and collecting the remote paths of every discovered compiler
+/// artifact. Build-script `out_dir`s are deliberately not collected: cargo names every one of
+/// them `out` (`target/<profile>/build/<pkg>-<hash>/out`), and every dependency with a build
+/// script emits one, so copying them all back into the same flat `--copy-artifacts` destination
+/// would have them overwrite each other.
+pub fn run_build_with_artifact_discovery(remote: &Remote, command: &str) -> Result<Vec<String>, String> {
+    // No `-t`: this path's stdout must stay exactly the newline-delimited JSON stream cargo
+    // emits, and pty allocation merges the remote stdout/stderr onto one channel before it
+    // reaches us, interleaving human-facing diagnostics into the parser's input.
+    let mut child = Command::new("ssh")
+        .env("LC_ALL", "C.UTF-8")
+        .args(&["-p", &remote.ssh_port.to_string()])
+        .arg(&remote.host)
+        .arg(command)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .stdin(Stdio::inherit())
+        .spawn()
+        .map_err(|e| format!("Failed to run cargo command remotely (error: {})", e))?;
+
+    let reader = BufReader::new(child.stdout.take().expect("stdout was piped"));
+    let artifacts = collect_artifacts_from_cargo_messages(reader);
+
+    let status = child
+        .wait()
+        .map_err(|e| format!("Failed to wait on remote cargo command (error: {})", e))?;
+    if !status.success() {
+        return Err(format!("Remote cargo command exited with status: {}", status));
+    }
+
+    Ok(artifacts)
+}
+
+/// Parse a `--message-format=json-render-diagnostics` stream, printing rendered diagnostics as
+/// they arrive and returning the remote paths of every discovered compiler artifact. Takes a
+/// plain `BufRead` rather than spawning `ssh` itself, so the parsing logic can be fed a canned
+/// stream in tests instead of only being exercised end-to-end over a real connection.
+pub fn collect_artifacts_from_cargo_messages<R: std::io::BufRead>(reader: R) -> Vec<String> {
+    let mut artifacts = Vec::new();
+
+    for message in Message::parse_stream(reader) {
+        match message {
+            Ok(Message::CompilerArtifact(artifact)) => {
+                if let Some(executable) = artifact.executable {
+                    artifacts.push(executable.to_string());
+                }
+            }
+            Ok(Message::BuildScriptExecuted(_)) => {}
+            Ok(Message::CompilerMessage(msg)) => {
+                if let Some(rendered) = msg.message.rendered {
+                    print!("{}", rendered);
+                }
+            }
+            Ok(Message::TextLine(line)) => {
+                println!("{}", line);
+            }
+            Ok(_) => {}
+            Err(e) => {
+                debug!("Failed to parse cargo json-message line (error: {})", e);
+            }
+        }
+    }
+
+    artifacts
+}
+
+/// Copy-back stage: rsync every `(remote_spec, local_dest)` pair back to the local machine in
+/// parallel, where `remote_spec` is already a full `host:path` rsync source.
+pub fn copy_back(pairs: Vec<(String, String)>, ssh_port: u16, checksum: bool) -> Result<(), Vec<String>> {
+    if pairs.is_empty() {
+        return Ok(());
+    }
+
+    info!("Transferring artifacts back to the local machine.");
+
+    let errors = Arc::new(Mutex::new(Vec::new()));
+    let threads: Vec<_> = pairs
+        .into_iter()
+        .map(|(remote_spec, local_dest)| {
+            let errors = Arc::clone(&errors);
+            thread::spawn(move || {
+                let mut rsync_back = Command::new("rsync");
+                rsync_back
+                    .arg("-a")
+                    .arg("--compress")
+                    .arg("-e")
+                    .arg(format!("ssh -p {}", ssh_port))
+                    .arg("--info=progress2");
+                if checksum {
+                    rsync_back.arg("--checksum");
+                }
+                rsync_back
+                    .arg(&remote_spec)
+                    .arg(format!("{}/", local_dest))
+                    .env("LC_ALL", "C.UTF-8")
+                    .stdout(Stdio::inherit())
+                    .stderr(Stdio::inherit())
+                    .stdin(Stdio::inherit());
+
+                let output = rsync_back.output();
+
+                match output {
+                    Ok(result) if result.status.success() => {
+                        info!(
+                            "Successfully transferred '{}' to '{}'",
+                            remote_spec, local_dest
+                        );
+                    }
+                    Ok(result) => {
+                        let message = format!(
+                            "Rsync failed for '{}' to '{}' with exit code: {}",
+                            remote_spec, local_dest, result.status
+                        );
+                        error!("{}", message);
+                        errors.lock().unwrap().push(message);
+                    }
+                    Err(e) => {
+                        let message = format!(
+                            "Failed to transfer '{}' to '{}' (error: {})",
+                            remote_spec, local_dest, e
+                        );
+                        error!("{}", message);
+                        errors.lock().unwrap().push(message);
+                    }
+                }
+            })
+        })
+        .collect();
+
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    let errors = Arc::try_unwrap(errors).unwrap().into_inner().unwrap();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Copy-back stage for a batch of paths that all share a common remote root and local root
+/// (e.g. `--sync-fix`'s set of unmodified-locally source files). Uses a single rsync invocation
+/// with `--files-from` instead of one SSH connection per file, so the transfer doesn't blow past
+/// the remote sshd's `MaxStartups`/`MaxSessions` limits on projects with many fixed-up files.
+pub fn copy_back_batch(
+    remote_root: &str,
+    local_root: &Utf8PathBuf,
+    paths: &[String],
+    ssh_port: u16,
+    checksum: bool,
+) -> Result<(), Vec<String>> {
+    if paths.is_empty() {
+        return Ok(());
+    }
+
+    info!(
+        "Transferring {} artifact(s) back to the local machine in one batch.",
+        paths.len()
+    );
+
+    let uid = SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    let files_from = std::env::temp_dir().join(format!("crunch-sync-fix-{}.list", uid));
+    std::fs::write(&files_from, paths.join("\n"))
+        .map_err(|e| vec![format!("Failed to write rsync file list: {}", e)])?;
+
+    let mut rsync_back = Command::new("rsync");
+    rsync_back
+        .arg("-a")
+        .arg("--compress")
+        .arg("-e")
+        .arg(format!("ssh -p {}", ssh_port))
+        .arg("--info=progress2")
+        .arg("--files-from")
+        .arg(&files_from);
+    if checksum {
+        rsync_back.arg("--checksum");
+    }
+    rsync_back
+        .arg(format!("{}/", remote_root))
+        .arg(format!("{}/", local_root))
+        .env("LC_ALL", "C.UTF-8")
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .stdin(Stdio::inherit());
+
+    let output = rsync_back.output();
+    let _ = std::fs::remove_file(&files_from);
+
+    match output {
+        Ok(result) if result.status.success() => {
+            info!(
+                "Successfully transferred {} artifact(s) from '{}' to '{}'",
+                paths.len(),
+                remote_root,
+                local_root
+            );
+            Ok(())
+        }
+        Ok(result) => Err(vec![format!(
+            "Rsync failed for '{}' to '{}' with exit code: {}",
+            remote_root, local_root, result.status
+        )]),
+        Err(e) => Err(vec![format!(
+            "Failed to transfer '{}' to '{}' (error: {})",
+            remote_root, local_root, e
+        )]),
+    }
+}
+
+/// Build the `--plan` JSON payload: the resolved build path, rsync argv, remote build command,
+/// copy-back pairs, and cleanup step, so a user can inspect exactly what a real run would do
+/// without touching ssh or rsync.
+#[allow(clippy::too_many_arguments)]
+pub fn build_plan(
+    remote: &Remote,
+    remote_path_behavior: &RemotePathBehavior,
+    build_path: &str,
+    rsync_to_argv: &[String],
+    build_command: &str,
+    artifact_discovery: bool,
+    sync_fix: bool,
+    copy_back_pairs: &[(String, String)],
+    cleanup_step: Option<&str>,
+) -> serde_json::Value {
+    json!({
+        "remote": {
+            "name": remote.name,
+            "host": remote.host,
+            "ssh_port": remote.ssh_port,
+        },
+        "remote_path_behavior": match remote_path_behavior {
+            RemotePathBehavior::Mirror => "mirror",
+            RemotePathBehavior::Tmp => "tmp",
+            RemotePathBehavior::Unique => "unique",
+        },
+        "build_path": build_path,
+        "rsync_to_argv": rsync_to_argv,
+        "build_command": build_command,
+        "artifact_discovery": artifact_discovery,
+        "sync_fix": sync_fix,
+        "copy_back_pairs": copy_back_pairs
+            .iter()
+            .map(|(source, dest)| json!({"source": source, "dest": dest}))
+            .collect::<Vec<_>>(),
+        "cleanup_step": cleanup_step,
+    })
+}
+
+/// Cleanup stage: `cargo clean` and remove the remote build directory. Only meaningful for
+/// [`RemotePathBehavior::Tmp`], which is the only behavior whose directory isn't meant to
+/// persist across runs.
+pub fn cleanup_remote(remote: &Remote, build_path: &str) -> Result<(), String> {
+    let output = Command::new("ssh")
+        .args(&["-p", &remote.ssh_port.to_string()])
+        .arg(&remote.host)
+        .arg(format!(
+            "cd '{}' && cargo clean && rm -r '{}'",
+            build_path, build_path
+        ))
+        .output()
+        .map_err(|e| format!("Could not run cleanup command (error: {})", e))?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "Failed to clean up temporary directory '{}': {}",
+            build_path,
+            String::from_utf8_lossy(&output.stderr)
+        ))
+    }
+}
+
+/// Default linker/runner pairs for cross-compiling from the build server to a
+/// foreign target triple. The runner lets `cargo test`/`cargo run` execute the
+/// resulting binaries transparently under QEMU.
+pub fn default_cross_toolchains() -> HashMap<&'static str, (&'static str, &'static str)> {
+    HashMap::from([
+        (
+            "aarch64-unknown-linux-gnu",
+            (
+                "aarch64-linux-gnu-gcc",
+                "qemu-aarch64 -L /usr/aarch64-linux-gnu",
+            ),
+        ),
+        (
+            "s390x-unknown-linux-gnu",
+            ("s390x-linux-gnu-gcc", "qemu-s390x -L /usr/s390x-linux-gnu"),
+        ),
+        (
+            "riscv64gc-unknown-linux-gnu",
+            (
+                "riscv64-linux-gnu-gcc",
+                "qemu-riscv64 -L /usr/riscv64-linux-gnu",
+            ),
+        ),
+    ])
+}
+
+/// Turn a target triple into the `CARGO_TARGET_<TRIPLE>_` env var prefix cargo expects.
+pub fn cargo_target_env_prefix(triple: &str) -> String {
+    format!("CARGO_TARGET_{}_", triple.to_uppercase().replace('-', "_"))
+}
+
+/// Parse `--cross-linker`/`--cross-runner` override entries of the form `triple=value`,
+/// layering them on top of the built-in defaults.
+pub fn build_cross_toolchains(
+    linker_overrides: &[String],
+    runner_overrides: &[String],
+) -> HashMap<String, (String, String)> {
+    let mut table: HashMap<String, (String, String)> = default_cross_toolchains()
+        .into_iter()
+        .map(|(triple, (linker, runner))| (triple.to_string(), (linker.to_string(), runner.to_string())))
+        .collect();
+
+    for entry in linker_overrides {
+        let mut parts = entry.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(triple), Some(linker)) => {
+                let runner = table
+                    .get(triple)
+                    .map(|(_, runner)| runner.clone())
+                    .unwrap_or_default();
+                table.insert(triple.to_string(), (linker.to_string(), runner));
+            }
+            _ => panic!("Invalid format for --cross-linker entry: {}", entry),
+        }
+    }
+
+    for entry in runner_overrides {
+        let mut parts = entry.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(triple), Some(runner)) => {
+                let linker = table
+                    .get(triple)
+                    .map(|(linker, _)| linker.clone())
+                    .unwrap_or_default();
+                table.insert(triple.to_string(), (linker, runner.to_string()));
+            }
+            _ => panic!("Invalid format for --cross-runner entry: {}", entry),
+        }
+    }
+
+    table
+}
+
+/// Resolve a `--copy-back` source glob under `target/<triple>/...` when cross-compiling,
+/// so users don't have to hand-write the target-specific path.
+pub fn resolve_copy_back_source(source: &str, target: Option<&str>) -> String {
+    let Some(target) = target else {
+        return source.to_string();
+    };
+    if let Some(rest) = source.strip_prefix("./target/") {
+        return format!("./target/{}/{}", target, rest);
+    }
+    if let Some(rest) = source.strip_prefix("target/") {
+        return format!("target/{}/{}", target, rest);
+    }
+    source.to_string()
+}
+
+/// List the git-tracked `.rs` and `Cargo.toml` files under `project_dir`, i.e. the files a
+/// remote `cargo fix`/`cargo clippy --fix` run might rewrite.
+pub fn tracked_source_files(project_dir: &Utf8PathBuf) -> Vec<String> {
+    let output = Command::new("git")
+        .args(&["-C", project_dir.as_str(), "ls-files"])
+        .output();
+    match output {
+        Ok(out) if out.status.success() => String::from_utf8_lossy(&out.stdout)
+            .lines()
+            .filter(|line| line.ends_with(".rs") || line.ends_with("Cargo.toml"))
+            .map(|line| line.to_string())
+            .collect(),
+        _ => {
+            error!("Failed to list tracked source files via 'git ls-files'");
+            Vec::new()
+        }
+    }
+}
+
+/// Fingerprint a single source file by mtime and content hash, so later we can tell whether it
+/// was touched locally since the push.
+pub fn snapshot_source(project_dir: &Utf8PathBuf, rel_path: &str) -> Option<(SystemTime, u64)> {
+    let full_path = project_dir.join(rel_path);
+    let mtime = std::fs::metadata(&full_path).ok()?.modified().ok()?;
+    let contents = std::fs::read(&full_path).ok()?;
+    let mut hasher = DefaultHasher::new();
+    contents.hash(&mut hasher);
+    Some((mtime, hasher.finish()))
+}
+
+/// Snapshot every tracked source file's mtime and content hash before pushing to the remote.
+pub fn snapshot_tracked_sources(project_dir: &Utf8PathBuf) -> HashMap<String, (SystemTime, u64)> {
+    tracked_source_files(project_dir)
+        .into_iter()
+        .filter_map(|path| snapshot_source(project_dir, &path).map(|snapshot| (path, snapshot)))
+        .collect()
+}
+
+/// Split the snapshotted files into those untouched locally since the push (safe to overwrite
+/// with the remote's fixed version) and those a local edit has since modified (must not be
+/// clobbered).
+pub fn partition_fix_sync_candidates(
+    project_dir: &Utf8PathBuf,
+    snapshot: &HashMap<String, (SystemTime, u64)>,
+) -> (Vec<String>, Vec<String>) {
+    let mut safe = Vec::new();
+    let mut locally_modified = Vec::new();
+    for (path, before) in snapshot {
+        match snapshot_source(project_dir, path) {
+            Some(after) if after == *before => safe.push(path.clone()),
+            _ => locally_modified.push(path.clone()),
+        }
+    }
+    (safe, locally_modified)
+}
+
+/// Build the `cargo <command> ...` argument list cargo will actually see, injecting `extra`
+/// flags (e.g. `--target <triple>`, `--message-format=...`) after the user's cargo arguments, or
+/// just before the user's own `--` separator if there is one. `command` is `trailing_var_arg`, so it may embed
+/// its own `--` (e.g. `crunch test -- --nocapture`); appending flags after that point would hand
+/// them to the test binary instead of cargo, silently dropping them.
+pub fn inject_cargo_args(command: &[String], extra: &[String]) -> Vec<String> {
+    if extra.is_empty() {
+        return command.to_vec();
+    }
+    let split = command.iter().position(|arg| arg == "--");
+    match split {
+        Some(index) => {
+            let mut result = command[..index].to_vec();
+            result.extend(extra.iter().cloned());
+            result.extend(command[index..].iter().cloned());
+            result
+        }
+        None => {
+            let mut result = command.to_vec();
+            result.extend(extra.iter().cloned());
+            result
+        }
+    }
+}
+
+pub fn extract_manifest_path(args: &[String]) -> Option<String> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--manifest-path" {
+            return args.next().cloned();
+        } else if arg.starts_with("--manifest-path=") {
+            return Some(arg.splitn(2, '=').nth(1).unwrap().to_string());
+        }
+    }
+    None
+}
+
+#[test]
+fn inject_cargo_args_inserts_before_trailing_separator() {
+    // No `--`: extra flags append at the end.
+    let command = vec!["build".to_string(), "--release".to_string()];
+    let extra = vec!["--target".to_string(), "aarch64-unknown-linux-gnu".to_string()];
+    assert_eq!(
+        inject_cargo_args(&command, &extra),
+        vec!["build", "--release", "--target", "aarch64-unknown-linux-gnu"]
+    );
+
+    // `--`: extra flags must land before it, not after (where they'd reach the test binary).
+    let command = vec![
+        "test".to_string(),
+        "--".to_string(),
+        "--nocapture".to_string(),
+    ];
+    assert_eq!(
+        inject_cargo_args(&command, &extra),
+        vec![
+            "test",
+            "--target",
+            "aarch64-unknown-linux-gnu",
+            "--",
+            "--nocapture"
+        ]
+    );
+
+    // No extra flags: command passed through unchanged.
+    assert_eq!(inject_cargo_args(&command, &[]), command);
+}
+
+#[test]
+fn extract_manifest_path_works() {
+    // Test next arg
+    let args = vec![
+        "build".to_string(),
+        "--release".to_string(),
+        "--manifest-path".to_string(),
+        "Cargo.toml".to_string(),
+    ];
+    assert_eq!(extract_manifest_path(&args), Some("Cargo.toml".to_string()));
+
+    // Test equals
+    let args = vec![
+        "build".to_string(),
+        "--release".to_string(),
+        "--manifest-path=Cargo.toml".to_string(),
+    ];
+    assert_eq!(extract_manifest_path(&args), Some("Cargo.toml".to_string()));
+
+    // Test none
+    let args = vec!["build".to_string(), "--release".to_string()];
+    assert_eq!(extract_manifest_path(&args), None);
+}
+
+#[test]
+fn resolve_copy_back_source_works() {
+    // No target: left untouched
+    assert_eq!(
+        resolve_copy_back_source("./target/release/foo", None),
+        "./target/release/foo"
+    );
+
+    // Target set: inject the triple after `target/`
+    assert_eq!(
+        resolve_copy_back_source(
+            "./target/release/foo",
+            Some("aarch64-unknown-linux-gnu")
+        ),
+        "./target/aarch64-unknown-linux-gnu/release/foo"
+    );
+    assert_eq!(
+        resolve_copy_back_source("target/release/foo", Some("s390x-unknown-linux-gnu")),
+        "target/s390x-unknown-linux-gnu/release/foo"
+    );
+
+    // Not a target/ path: left untouched even with a target set
+    assert_eq!(
+        resolve_copy_back_source("my-binary", Some("s390x-unknown-linux-gnu")),
+        "my-binary"
+    );
+}
+
+#[test]
+fn build_cross_toolchains_applies_overrides() {
+    let table = build_cross_toolchains(
+        &["aarch64-unknown-linux-gnu=aarch64-linux-gnu-gcc-12".to_string()],
+        &["riscv64gc-unknown-linux-gnu=qemu-riscv64-static".to_string()],
+    );
+
+    // Overridden linker keeps the default runner
+    assert_eq!(
+        table.get("aarch64-unknown-linux-gnu"),
+        Some(&(
+            "aarch64-linux-gnu-gcc-12".to_string(),
+            "qemu-aarch64 -L /usr/aarch64-linux-gnu".to_string()
+        ))
+    );
+    // Overridden runner keeps the default linker
+    assert_eq!(
+        table.get("riscv64gc-unknown-linux-gnu"),
+        Some(&(
+            "riscv64-linux-gnu-gcc".to_string(),
+            "qemu-riscv64-static".to_string()
+        ))
+    );
+    // Untouched entries keep both defaults
+    assert_eq!(
+        table.get("s390x-unknown-linux-gnu"),
+        Some(&(
+            "s390x-linux-gnu-gcc".to_string(),
+            "qemu-s390x -L /usr/s390x-linux-gnu".to_string()
+        ))
+    );
+}
+
+#[test]
+fn select_remote_works() {
+    let remotes = vec![
+        Remote {
+            name: "x86".to_string(),
+            host: "build-x86".to_string(),
+            ssh_port: default_ssh_port(),
+            temp_dir: default_temp_dir(),
+            env: default_env_profile(),
+            target: None,
+        },
+        Remote {
+            name: "arm".to_string(),
+            host: "build-arm".to_string(),
+            ssh_port: default_ssh_port(),
+            temp_dir: default_temp_dir(),
+            env: default_env_profile(),
+            target: Some("aarch64-unknown-linux-gnu".to_string()),
+        },
+    ];
+    let workspace_root = Utf8PathBuf::from("/home/user/project");
+
+    // No selector: first configured remote
+    assert_eq!(
+        select_remote(&remotes, None, None, &workspace_root).name,
+        "x86"
+    );
+
+    // By name
+    assert_eq!(
+        select_remote(&remotes, Some("arm"), None, &workspace_root).name,
+        "arm"
+    );
+
+    // Auto matches by advertised target
+    assert_eq!(
+        select_remote(
+            &remotes,
+            Some("auto"),
+            Some("aarch64-unknown-linux-gnu"),
+            &workspace_root
+        )
+        .name,
+        "arm"
+    );
+
+    // Auto falls back to round-robin hashing when no remote advertises the target
+    let fallback = select_remote(&remotes, Some("auto"), Some("s390x-unknown-linux-gnu"), &workspace_root);
+    assert!(remotes.iter().any(|r| r.name == fallback.name));
+}
+
+#[test]
+fn resolve_build_path_variants() {
+    let project_dir = Utf8PathBuf::from("/home/user/project");
+    let remote = Remote {
+        name: "crunch".to_string(),
+        host: "crunch".to_string(),
+        ssh_port: default_ssh_port(),
+        temp_dir: default_temp_dir(),
+        env: default_env_profile(),
+        target: None,
+    };
+
+    assert_eq!(
+        resolve_build_path(&RemotePathBehavior::Mirror, &remote, &project_dir),
+        "/home/user/project"
+    );
+    assert!(resolve_build_path(&RemotePathBehavior::Tmp, &remote, &project_dir)
+        .starts_with("/tmp/crunch-project-"));
+    assert!(resolve_build_path(&RemotePathBehavior::Unique, &remote, &project_dir)
+        .starts_with("~/crunch-builds/project-"));
+}
+
+#[test]
+fn collect_artifacts_from_cargo_messages_parses_compiler_artifacts() {
+    // A canned `--message-format=json-render-diagnostics` stream: a build-script-executed line
+    // (out-dir, deliberately ignored), a compiler-artifact for a lib (no executable), a
+    // compiler-artifact for a bin (the one we should collect), and a trailing build-finished line.
+    let stream = r#"{"reason":"build-script-executed","package_id":"fixture 0.1.0 (path+file:///fixture)","linked_libs":[],"linked_paths":[],"cfgs":[],"env":[],"out_dir":"/build/target/debug/build/fixture-abc123/out"}
+{"reason":"compiler-artifact","package_id":"fixture 0.1.0 (path+file:///fixture)","manifest_path":"Cargo.toml","target":{"kind":["lib"],"crate_types":["lib"],"name":"fixture","src_path":"/fixture/src/lib.rs","edition":"2021","doc":true,"doctest":true,"test":true},"profile":{"opt_level":"0","debuginfo":2,"debug_assertions":true,"overflow_checks":true,"test":false},"features":[],"filenames":["/build/target/debug/libfixture.rlib"],"executable":null,"fresh":false}
+{"reason":"compiler-artifact","package_id":"fixture 0.1.0 (path+file:///fixture)","manifest_path":"Cargo.toml","target":{"kind":["bin"],"crate_types":["bin"],"name":"fixture","src_path":"/fixture/src/main.rs","edition":"2021","doc":true,"doctest":false,"test":true},"profile":{"opt_level":"0","debuginfo":2,"debug_assertions":true,"overflow_checks":true,"test":false},"features":[],"filenames":["/build/target/debug/fixture"],"executable":"/build/target/debug/fixture","fresh":false}
+{"reason":"build-finished","success":true}
+"#;
+
+    let artifacts = collect_artifacts_from_cargo_messages(stream.as_bytes());
+    assert_eq!(artifacts, vec!["/build/target/debug/fixture".to_string()]);
+}
+
+#[test]
+fn build_plan_shape() {
+    let remote = Remote {
+        name: "crunch".to_string(),
+        host: "crunch".to_string(),
+        ssh_port: default_ssh_port(),
+        temp_dir: default_temp_dir(),
+        env: default_env_profile(),
+        target: None,
+    };
+
+    let plan = build_plan(
+        &remote,
+        &RemotePathBehavior::Tmp,
+        "/tmp/crunch-project-1",
+        &["-a".to_string(), "--delete".to_string()],
+        "cd /tmp/crunch-project-1; cargo build --release",
+        false,
+        false,
+        &[("target/release/foo".to_string(), ".".to_string())],
+        Some("cd '/tmp/crunch-project-1' && cargo clean && rm -r '/tmp/crunch-project-1'"),
+    );
+
+    assert_eq!(
+        plan,
+        json!({
+            "remote": {
+                "name": "crunch",
+                "host": "crunch",
+                "ssh_port": 22,
+            },
+            "remote_path_behavior": "tmp",
+            "build_path": "/tmp/crunch-project-1",
+            "rsync_to_argv": ["-a", "--delete"],
+            "build_command": "cd /tmp/crunch-project-1; cargo build --release",
+            "artifact_discovery": false,
+            "sync_fix": false,
+            "copy_back_pairs": [{"source": "target/release/foo", "dest": "."}],
+            "cleanup_step": "cd '/tmp/crunch-project-1' && cargo clean && rm -r '/tmp/crunch-project-1'",
+        })
+    );
+}
+
+#[test]
+fn resolve_build_path_unique_honors_configured_temp_dir() {
+    let project_dir = Utf8PathBuf::from("/home/user/project");
+    let remote = Remote {
+        name: "crunch".to_string(),
+        host: "crunch".to_string(),
+        ssh_port: default_ssh_port(),
+        temp_dir: "/srv/builds".to_string(),
+        env: default_env_profile(),
+        target: None,
+    };
+
+    assert!(resolve_build_path(&RemotePathBehavior::Unique, &remote, &project_dir)
+        .starts_with("/srv/builds/project-"));
+}
@@ -2,36 +2,19 @@
 //!
 //! crunch seamlessly integrates cutting-edge hardware into your local development environment.
 
-use clap::{command, Parser, ValueEnum};
+use clap::{command, Parser};
 use env_logger;
 use log::{debug, error, info};
-use std::{
-    hash::{DefaultHasher, Hash, Hasher},
-    process::{exit, Command, Stdio},
-    sync::{Arc, Mutex},
-    thread,
-    time::{SystemTime, UNIX_EPOCH},
-};
-use cargo_metadata::camino::Utf8PathBuf;
-
-#[derive(Debug, Clone)]
-pub struct Remote {
-    pub name: String,
-    pub host: String,
-    pub ssh_port: u16,
-    pub temp_dir: String,
-    pub env: String,
-}
+use std::{collections::HashMap, process::exit};
 
-#[derive(Debug, Clone, ValueEnum)]
-enum RemotePathBehavior {
-    /// Mirror the local directory structure on the remote server (default)
-    Mirror,
-    /// Use a temporary directory on the remote server that cleans up afterwards
-    Tmp,
-    /// Use a unique persistent directory in the user's home directory for each project
-    Unique,
-}
+use cargo_metadata::camino::Utf8PathBuf;
+use crunch::{
+    build_cross_toolchains, build_plan, build_rsync_to_argv, cargo_target_env_prefix,
+    cleanup_remote, copy_back, copy_back_batch, extract_manifest_path, inject_cargo_args,
+    load_config, partition_fix_sync_candidates, resolve_build_path, resolve_copy_back_source,
+    run_build, run_build_with_artifact_discovery, select_remote, snapshot_tracked_sources,
+    transfer_sources, RemotePathBehavior,
+};
 
 #[derive(Parser, Debug)]
 #[command(
@@ -81,6 +64,62 @@ struct Args {
     #[arg(long = "remote-path", required = false, default_value = "mirror")]
     remote_path: RemotePathBehavior,
 
+    /// Auto-discover build artifacts instead of hand-writing `--copy-back` globs, by parsing
+    /// `--message-format=json-render-diagnostics` output from the remote cargo invocation and
+    /// rsyncing every `compiler-artifact` executable back to this local directory.
+    /// Build-script out-dirs are not collected (see `run_build_with_artifact_discovery`).
+    /// Ignored if `--copy-back` is supplied.
+    ///
+    /// Example: `--copy-artifacts ./dist build --release`
+    #[arg(long = "copy-artifacts", required = false)]
+    copy_artifacts: Option<String>,
+
+    /// Print the full execution plan (resolved build path, rsync argv, remote build command,
+    /// copy-back pairs, cleanup step) as JSON and exit without touching ssh or rsync.
+    #[arg(long = "plan", required = false)]
+    plan: bool,
+
+    /// Select which configured remote to build on by name, or `auto` to pick one based on
+    /// `--target` (falling back to round-robin). Defaults to the first remote in
+    /// `crunch.toml`/`~/.config/crunch/config.toml`, or the built-in default if unconfigured.
+    #[arg(long = "remote", required = false)]
+    remote: Option<String>,
+
+    /// After the cargo command finishes, sync back the *modified source files* (`.rs`,
+    /// `Cargo.toml`) from the remote instead of build artifacts. Refuses to overwrite any file
+    /// that was also modified locally since the push, to avoid clobbering concurrent edits.
+    /// Intended for `cargo fix`/`cargo clippy --fix`, which rewrite sources in place on the
+    /// remote. Takes precedence over `--copy-back`/`--copy-artifacts`.
+    #[arg(long = "sync-fix", required = false)]
+    sync_fix: bool,
+
+    /// Passthrough for cargo fix's own `--broken-code` flag, appended to the remote cargo
+    /// invocation.
+    #[arg(long = "broken-code", required = false)]
+    broken_code: bool,
+
+    /// Cross-compile for a target triple that differs from the remote build server's
+    /// architecture. Injects `CARGO_TARGET_<TRIPLE>_LINKER`/`_RUNNER` into the remote
+    /// build environment so the right cross toolchain and QEMU runner are used.
+    ///
+    /// Example: `--target aarch64-unknown-linux-gnu build --release`
+    #[arg(long = "target", required = false)]
+    target: Option<String>,
+
+    /// Override the linker used for a cross-compilation target triple.
+    /// Specify multiple entries using delimiter ','.
+    ///
+    /// Example: `--cross-linker "aarch64-unknown-linux-gnu=aarch64-linux-gnu-gcc-12"`
+    #[arg(long = "cross-linker", required = false, value_delimiter = ',')]
+    cross_linker: Vec<String>,
+
+    /// Override the runner used to execute binaries for a cross-compilation target triple.
+    /// Specify multiple entries using delimiter ','.
+    ///
+    /// Example: `--cross-runner "aarch64-unknown-linux-gnu=qemu-aarch64-static"`
+    #[arg(long = "cross-runner", required = false, value_delimiter = ',')]
+    cross_runner: Vec<String>,
+
     /// The cargo command to execute
     ///
     /// Example: `build --release`
@@ -88,12 +127,6 @@ struct Args {
     command: Vec<String>,
 }
 
-fn uid_from_path(path: &Utf8PathBuf) -> u64 {
-    let mut hasher = DefaultHasher::new();
-    path.as_str().hash(&mut hasher);
-    hasher.finish()
-}
-
 fn main() {
     env_logger::builder()
         .filter_level(log::LevelFilter::Info)
@@ -108,7 +141,10 @@ fn main() {
         .filter_map(|entry| {
             let mut parts = entry.splitn(2, ':');
             match (parts.next(), parts.next()) {
-                (Some(source), Some(dest)) => Some((source.to_string(), dest.to_string())),
+                (Some(source), Some(dest)) => Some((
+                    resolve_copy_back_source(source, args.target.as_deref()),
+                    dest.to_string(),
+                )),
                 _ => {
                     panic!("Invalid format for --copy-back entry: {}", entry);
                 }
@@ -116,11 +152,13 @@ fn main() {
         })
         .collect();
 
+    let cross_toolchains = build_cross_toolchains(&args.cross_linker, &args.cross_runner);
+
     // Run it once redirecting logs to terminal to ensure if something needs to be installed, user
     // sees it.
-    Command::new("cargo")
+    std::process::Command::new("cargo")
         .args(&["metadata", "--no-deps", "--format-version", "1"])
-        .stderr(Stdio::inherit())
+        .stderr(std::process::Stdio::inherit())
         .output()
         .unwrap_or_else(|e| {
             error!("Failed to run cargo command remotely (error: {})", e);
@@ -131,86 +169,67 @@ fn main() {
     let mut metadata_cmd = cargo_metadata::MetadataCommand::new();
     metadata_cmd.manifest_path(manifest_path).no_deps();
     let project_metadata = metadata_cmd.exec().unwrap();
-    let project_dir = project_metadata.workspace_root;
-
-    let remote = Remote {
-        name: "crunch".to_string(),
-        host: "crunch".to_string(),
-        ssh_port: 22,
-        temp_dir: "~/crunch-builds".to_string(),
-        env: "~/.profile".to_string(),
-    };
+    let project_dir: Utf8PathBuf = project_metadata.workspace_root;
 
-    let build_server = remote.host;
-
-    let build_path = match args.remote_path {
-        RemotePathBehavior::Tmp => {
-            // Generate UID locally to avoid RTT latency
-            let project_name = project_dir
-                .file_name()
-                .expect("Project dir should always exist");
-            let uid = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_nanos();
-            let temp_path = format!("/tmp/crunch-{}-{}", project_name, uid);
-            info!("Using temporary directory: {}", temp_path);
-            temp_path
-        }
-        RemotePathBehavior::Unique => {
-            let project_name = project_dir
-                .file_name()
-                .expect("Project dir should always exist");
-            let uid = uid_from_path(&project_dir);
-            let unique_path = format!("~/crunch-builds/{}-{}", project_name, uid);
-
-            debug!("Using unique persistent directory: {}", unique_path);
-            unique_path
-        }
-        RemotePathBehavior::Mirror => project_dir.to_string(),
-    };
+    let remotes = load_config();
+    let remote = select_remote(&remotes, args.remote.as_deref(), args.target.as_deref(), &project_dir)
+        .clone();
+
+    let build_server = remote.host.clone();
 
+    let build_path = resolve_build_path(&args.remote_path, &remote, &project_dir);
     debug!("Using build path: {}", build_path);
 
-    info!("Transferring sources to remote: {}", build_path);
-    let mut rsync_to = Command::new("rsync");
-    rsync_to
-        .arg("-a".to_owned())
-        .arg("--delete")
-        .arg("--compress")
-        .arg("-e")
-        .arg(format!("ssh -p {}", remote.ssh_port))
-        .arg("--info=progress2")
-        .arg("--exclude")
-        .arg("target");
-
-    args.exclude.iter().for_each(|exclude| {
-        rsync_to.arg("--exclude").arg(exclude);
-    });
+    let rsync_to_argv = build_rsync_to_argv(&remote, &project_dir, &build_path, &args.exclude);
 
-    let rsync_path_arg = format!("mkdir -p {} && rsync", build_path);
-
-    rsync_to
-        .arg("--rsync-path")
-        .arg(rsync_path_arg)
-        .arg(format!("{}/", project_dir.to_string()))
-        .arg(format!("{}:{}", build_server, build_path))
-        .env("LC_ALL", "C.UTF-8")
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .stdin(Stdio::inherit())
-        .output()
-        .unwrap_or_else(|e| {
-            error!("Failed to transfer project to build server (error: {})", e);
-            exit(-4);
-        });
+    let cross_env = match &args.target {
+        Some(target) => match cross_toolchains.get(target) {
+            Some((linker, runner)) => {
+                let prefix = cargo_target_env_prefix(target);
+                format!(
+                    "export {}LINKER={}; export {}RUNNER=\"{}\"; ",
+                    prefix, linker, prefix, runner
+                )
+            }
+            None => {
+                error!("No cross toolchain known for target '{}'; pass --cross-linker/--cross-runner to define one", target);
+                exit(-2);
+            }
+        },
+        None => String::new(),
+    };
+
+    // Auto-discover artifacts via `--message-format=json-render-diagnostics` rather than
+    // making the user hand-write `--copy-back` globs. Explicit `--copy-back` wins if supplied.
+    let use_artifact_discovery = args.copy_artifacts.is_some() && copy_back_pairs.is_empty();
+
+    let mut extra_cargo_args = Vec::new();
+    if let Some(target) = &args.target {
+        extra_cargo_args.push("--target".to_string());
+        extra_cargo_args.push(target.clone());
+    }
+    if use_artifact_discovery {
+        extra_cargo_args.push("--message-format=json-render-diagnostics".to_string());
+    }
+    if args.broken_code {
+        extra_cargo_args.push("--broken-code".to_string());
+    }
+    if args.sync_fix {
+        // The default `--exclude` value drops `.git` from the transfer, so the remote tree
+        // normally has no VCS for cargo to check. `--sync-fix` already guards against
+        // clobbering concurrent edits via its own mtime/hash snapshot, so cargo's VCS check would
+        // only get in the way.
+        extra_cargo_args.push("--allow-no-vcs".to_string());
+    }
+    let cargo_args = inject_cargo_args(&args.command, &extra_cargo_args);
 
     let build_command = format!(
-        "export CC=gcc; export CXX=g++; source {}; cd {}; {} cargo {}",
+        "export CC=gcc; export CXX=g++; {}source {}; cd {}; {} cargo {}",
+        cross_env,
         remote.env,
         build_path,
         args.build_env,
-        args.command.join(" "),
+        cargo_args.join(" "),
     );
 
     // Add the post_cargo command to the build_command, if it exists
@@ -222,158 +241,112 @@ fn main() {
     } else {
         build_command
     };
-    Command::new("ssh")
-        .env("LC_ALL", "C.UTF-8")
-        .args(&["-p", &remote.ssh_port.to_string()])
-        .arg("-t")
-        .arg(&build_server)
-        .arg(command)
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .stdin(Stdio::inherit())
-        .output()
-        .unwrap_or_else(|e| {
-            error!("Failed to run cargo command remotely (error: {})", e);
+
+    let cleanup_step = if matches!(args.remote_path, RemotePathBehavior::Tmp) {
+        Some(format!(
+            "cd '{}' && cargo clean && rm -r '{}'",
+            build_path, build_path
+        ))
+    } else {
+        None
+    };
+
+    if args.plan {
+        let plan = build_plan(
+            &remote,
+            &args.remote_path,
+            &build_path,
+            &rsync_to_argv,
+            &command,
+            use_artifact_discovery,
+            args.sync_fix,
+            &copy_back_pairs,
+            cleanup_step.as_deref(),
+        );
+        println!("{}", serde_json::to_string_pretty(&plan).unwrap());
+        return;
+    }
+
+    let source_snapshot = if args.sync_fix {
+        snapshot_tracked_sources(&project_dir)
+    } else {
+        HashMap::new()
+    };
+
+    info!("Transferring sources to remote: {}", build_path);
+    transfer_sources(&rsync_to_argv).unwrap_or_else(|e| {
+        error!("{}", e);
+        exit(-4);
+    });
+
+    let discovered_artifacts = if use_artifact_discovery {
+        run_build_with_artifact_discovery(&remote, &command).unwrap_or_else(|e| {
+            error!("{}", e);
+            exit(-5);
+        })
+    } else {
+        run_build(&remote, &command).unwrap_or_else(|e| {
+            error!("{}", e);
             exit(-5);
         });
+        Vec::new()
+    };
 
-    if !copy_back_pairs.is_empty() {
-        info!("Transferring artifacts back to the local machine.");
-
-        let errors = Arc::new(Mutex::new(Vec::new()));
-        let threads: Vec<_> = copy_back_pairs
+    if args.sync_fix {
+        info!("Checking for local edits made since the push before syncing remote fixes back...");
+        let (safe, locally_modified) = partition_fix_sync_candidates(&project_dir, &source_snapshot);
+        for path in &locally_modified {
+            error!(
+                "Refusing to overwrite '{}': it was modified locally since the push",
+                path
+            );
+        }
+        let remote_root = format!("{}:{}", build_server, build_path);
+        copy_back_batch(&remote_root, &project_dir, &safe, remote.ssh_port, true).unwrap_or_else(
+            |errors| {
+                for error in errors {
+                    eprintln!("{}", error);
+                }
+                exit(-6);
+            },
+        );
+    } else if use_artifact_discovery {
+        let local_dest = args.copy_artifacts.expect("checked by use_artifact_discovery");
+        let pairs: Vec<(String, String)> = discovered_artifacts
+            .into_iter()
+            .map(|remote_path| (format!("{}:{}", build_server, remote_path), local_dest.clone()))
+            .collect();
+        copy_back(pairs, remote.ssh_port, false).unwrap_or_else(|errors| {
+            for error in errors {
+                eprintln!("{}", error);
+            }
+            exit(-6);
+        });
+    } else {
+        let pairs: Vec<(String, String)> = copy_back_pairs
             .into_iter()
             .map(|(remote_source, local_dest)| {
-                let errors = Arc::clone(&errors);
-                let build_server = build_server.clone();
-                let build_path = build_path.clone();
-                thread::spawn(move || {
-                    let mut rsync_back = Command::new("rsync");
-                    rsync_back
-                        .arg("-a")
-                        .arg("--compress")
-                        .arg("-e")
-                        .arg(format!("ssh -p {}", remote.ssh_port))
-                        .arg("--info=progress2")
-                        .arg(format!(
-                            "{}:{}/{}",
-                            &build_server, build_path, remote_source
-                        ))
-                        .arg(format!("{}/", local_dest))
-                        .env("LC_ALL", "C.UTF-8")
-                        .stdout(Stdio::inherit())
-                        .stderr(Stdio::inherit())
-                        .stdin(Stdio::inherit());
-
-                    let output = rsync_back.output();
-
-                    match output {
-                        Ok(result) if result.status.success() => {
-                            info!(
-                                "Successfully transferred '{}' to '{}'",
-                                remote_source, local_dest
-                            );
-                        }
-                        Ok(result) => {
-                            let message = format!(
-                                "Rsync failed for '{}' to '{}' with exit code: {}",
-                                remote_source, local_dest, result.status
-                            );
-                            error!("{}", message);
-                            errors.lock().unwrap().push(message);
-                        }
-                        Err(e) => {
-                            let message = format!(
-                                "Failed to transfer '{}' to '{}' (error: {})",
-                                remote_source, local_dest, e
-                            );
-                            error!("{}", message);
-                            errors.lock().unwrap().push(message);
-                        }
-                    }
-                })
+                (
+                    format!("{}:{}/{}", build_server, build_path, remote_source),
+                    local_dest,
+                )
             })
             .collect();
-
-        for thread in threads {
-            thread.join().unwrap();
-        }
-
-        let errors = errors.lock().unwrap();
-        if !errors.is_empty() {
-            for error in errors.iter() {
+        copy_back(pairs, remote.ssh_port, false).unwrap_or_else(|errors| {
+            for error in errors {
                 eprintln!("{}", error);
             }
             exit(-6);
-        }
+        });
     }
 
     // Clean up temporary directory if we created one
     if matches!(args.remote_path, RemotePathBehavior::Tmp) {
         info!("Cleaning up temporary directory on remote server...");
-
-        let cleanup_result = Command::new("ssh")
-            .args(&["-p", &remote.ssh_port.to_string()])
-            .arg(&build_server)
-            .arg(format!(
-                "cd '{}' && cargo clean && rm -r '{}'",
-                build_path, build_path
-            ))
-            .output();
-
-        match cleanup_result {
-            Ok(output) if output.status.success() => {
-                debug!(
-                    "Successfully cleaned up temporary directory: {}",
-                    build_path
-                );
-            }
-            Ok(output) => {
-                debug!(
-                    "Warning: Failed to clean up temporary directory '{}': {}",
-                    build_path,
-                    String::from_utf8_lossy(&output.stderr)
-                );
-            }
-            Err(e) => {
-                debug!("Warning: Could not run cleanup command (error: {})", e);
-            }
-        }
-    }
-}
-
-fn extract_manifest_path(args: &[String]) -> Option<String> {
-    let mut args = args.iter();
-    while let Some(arg) = args.next() {
-        if arg == "--manifest-path" {
-            return args.next().cloned();
-        } else if arg.starts_with("--manifest-path=") {
-            return Some(arg.splitn(2, '=').nth(1).unwrap().to_string());
+        if let Err(e) = cleanup_remote(&remote, &build_path) {
+            debug!("Warning: {}", e);
+        } else {
+            debug!("Successfully cleaned up temporary directory: {}", build_path);
         }
     }
-    None
-}
-
-#[test]
-fn extract_manifest_path_works() {
-    // Test next arg
-    let args = vec![
-        "build".to_string(),
-        "--release".to_string(),
-        "--manifest-path".to_string(),
-        "Cargo.toml".to_string(),
-    ];
-    assert_eq!(extract_manifest_path(&args), Some("Cargo.toml".to_string()));
-
-    // Test equals
-    let args = vec![
-        "build".to_string(),
-        "--release".to_string(),
-        "--manifest-path=Cargo.toml".to_string(),
-    ];
-    assert_eq!(extract_manifest_path(&args), Some("Cargo.toml".to_string()));
-
-    // Test none
-    let args = vec!["build".to_string(), "--release".to_string()];
-    assert_eq!(extract_manifest_path(&args), None);
 }
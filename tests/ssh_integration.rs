@@ -0,0 +1,273 @@
+//! End-to-end tests against a throwaway sshd container, exercising the real
+//! transfer -> build -> copy-back -> cleanup pipeline instead of mocking it out.
+//!
+//! Requires `docker` on the host; skips gracefully (with a message on stderr) when it isn't
+//! available rather than failing the suite.
+
+use cargo_metadata::camino::Utf8PathBuf;
+use crunch::{build_rsync_to_argv, cleanup_remote, copy_back, resolve_build_path, transfer_sources, Remote, RemotePathBehavior};
+use std::process::Command;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+const IMAGE_TAG: &str = "crunch-ssh-test-fixture";
+static UNIQUE_SUFFIX: AtomicU32 = AtomicU32::new(0);
+
+/// A disposable local directory, removed when it goes out of scope.
+struct ScratchDir(Utf8PathBuf);
+
+impl ScratchDir {
+    fn new(label: &str) -> Self {
+        let suffix = UNIQUE_SUFFIX.fetch_add(1, Ordering::Relaxed);
+        let path = Utf8PathBuf::from(format!(
+            "{}/crunch-test-{}-{}-{}",
+            std::env::temp_dir().to_string_lossy(),
+            label,
+            std::process::id(),
+            suffix
+        ));
+        std::fs::create_dir_all(&path).expect("failed to create scratch dir");
+        Self(path)
+    }
+
+    fn path(&self) -> &Utf8PathBuf {
+        &self.0
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A minimal crate-shaped project to transfer: a `Cargo.toml`, a source file, and a file that
+/// tests ask crunch to `--exclude`.
+fn sample_project() -> ScratchDir {
+    let dir = ScratchDir::new("project");
+    std::fs::write(
+        dir.path().join("Cargo.toml"),
+        "[package]\nname = \"fixture\"\nversion = \"0.1.0\"\n",
+    )
+    .unwrap();
+    std::fs::create_dir_all(dir.path().join("src")).unwrap();
+    std::fs::write(dir.path().join("src/main.rs"), "fn main() {}\n").unwrap();
+    std::fs::write(dir.path().join("secret.txt"), "do not transfer me\n").unwrap();
+    dir
+}
+
+struct SshdContainer {
+    container_id: String,
+    port: u16,
+}
+
+impl SshdContainer {
+    /// Build and start the sshd fixture, returning `None` (with a stderr note) if docker isn't
+    /// usable in this environment.
+    fn start() -> Option<Self> {
+        let docker_available = Command::new("docker")
+            .arg("info")
+            .output()
+            .map(|o| o.status.success())
+            .unwrap_or(false);
+        if !docker_available {
+            eprintln!("Skipping SSH integration test: docker is not available");
+            return None;
+        }
+
+        let build_status = Command::new("docker")
+            .args(["build", "-t", IMAGE_TAG, "tests/docker/sshd"])
+            .status()
+            .expect("failed to invoke docker build");
+        if !build_status.success() {
+            eprintln!("Skipping SSH integration test: failed to build the sshd fixture image");
+            return None;
+        }
+
+        let run_output = Command::new("docker")
+            .args(["run", "-d", "--rm", "-P", IMAGE_TAG])
+            .output()
+            .expect("failed to invoke docker run");
+        if !run_output.status.success() {
+            eprintln!("Skipping SSH integration test: failed to start the sshd fixture container");
+            return None;
+        }
+        let container_id = String::from_utf8_lossy(&run_output.stdout).trim().to_string();
+
+        let port_output = Command::new("docker")
+            .args(["port", &container_id, "22/tcp"])
+            .output()
+            .expect("failed to invoke docker port");
+        let mapping = String::from_utf8_lossy(&port_output.stdout);
+        let port: u16 = mapping
+            .trim()
+            .rsplit(':')
+            .next()
+            .expect("docker port output should contain a host port")
+            .parse()
+            .expect("docker port output should be a valid port number");
+
+        // Give sshd a moment to come up before the first connection attempt.
+        std::thread::sleep(std::time::Duration::from_millis(750));
+
+        // Git checks out regular files (including this fixture key) at 644. OpenSSH refuses
+        // group/other-readable private keys, so pubkey auth would fail on a fresh clone unless
+        // we tighten the mode ourselves rather than relying on the checkout's ambient perms.
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(
+            "tests/docker/sshd/test_key",
+            std::fs::Permissions::from_mode(0o600),
+        )
+        .expect("failed to restrict test_key permissions");
+
+        Some(Self { container_id, port })
+    }
+
+    fn remote(&self, name: &str) -> Remote {
+        Remote {
+            name: name.to_string(),
+            host: "crunch-test@localhost".to_string(),
+            ssh_port: self.port,
+            temp_dir: "~/crunch-builds".to_string(),
+            env: "~/.profile".to_string(),
+            target: None,
+        }
+    }
+
+    fn ssh_ok(&self, remote: &Remote, script: &str) -> bool {
+        Command::new("ssh")
+            .args([
+                "-p",
+                &remote.ssh_port.to_string(),
+                "-o",
+                "StrictHostKeyChecking=no",
+                "-i",
+                "tests/docker/sshd/test_key",
+                &remote.host,
+                script,
+            ])
+            .status()
+            .expect("ssh should be invocable")
+            .success()
+    }
+}
+
+impl Drop for SshdContainer {
+    fn drop(&mut self) {
+        let _ = Command::new("docker")
+            .args(["kill", &self.container_id])
+            .output();
+    }
+}
+
+#[test]
+fn transfer_honors_exclude() {
+    let Some(container) = SshdContainer::start() else {
+        return;
+    };
+    let remote = container.remote("exclude-test");
+    let project = sample_project();
+    let build_path = resolve_build_path(&RemotePathBehavior::Mirror, &remote, project.path());
+
+    let argv = build_rsync_to_argv(&remote, project.path(), &build_path, &["secret.txt".to_string()]);
+    transfer_sources(&argv).expect("transfer should succeed");
+
+    assert!(
+        container.ssh_ok(&remote, &format!("test -f '{}/Cargo.toml'", build_path)),
+        "non-excluded file should have been transferred"
+    );
+    assert!(
+        !container.ssh_ok(&remote, &format!("test -f '{}/secret.txt'", build_path)),
+        "excluded file should not have been transferred"
+    );
+}
+
+#[test]
+fn remote_path_behavior_variants() {
+    let Some(container) = SshdContainer::start() else {
+        return;
+    };
+    let remote = container.remote("path-behavior-test");
+    let project = sample_project();
+
+    // Mirror: the build path is exactly the local workspace root.
+    let mirror_path = resolve_build_path(&RemotePathBehavior::Mirror, &remote, project.path());
+    assert_eq!(mirror_path, project.path().to_string());
+
+    // Tmp: the build path lives under /tmp/crunch-*.
+    let tmp_path = resolve_build_path(&RemotePathBehavior::Tmp, &remote, project.path());
+    assert!(tmp_path.starts_with("/tmp/crunch-"));
+
+    // Unique: the build path is a stable hash of the workspace root, so repeated runs for the
+    // same project land in the same persistent directory.
+    let unique_path_a = resolve_build_path(&RemotePathBehavior::Unique, &remote, project.path());
+    let unique_path_b = resolve_build_path(&RemotePathBehavior::Unique, &remote, project.path());
+    assert_eq!(unique_path_a, unique_path_b);
+    assert!(unique_path_a.starts_with("~/crunch-builds/"));
+
+    // Exercise Unique end-to-end: the transfer lands in the hashed `~/crunch-builds/<name>-<uid>`
+    // directory on the remote (it has no cleanup step, so it's expected to persist).
+    let argv = build_rsync_to_argv(&remote, project.path(), &unique_path_a, &[]);
+    transfer_sources(&argv).expect("transfer should succeed");
+    assert!(
+        container.ssh_ok(&remote, &format!("test -d '{}'", unique_path_a)),
+        "Unique build path should exist on the remote after transfer"
+    );
+
+    // Exercise Tmp end-to-end: the transfer creates the directory, and cleanup removes it.
+    let argv = build_rsync_to_argv(&remote, project.path(), &tmp_path, &[]);
+    transfer_sources(&argv).expect("transfer should succeed");
+    assert!(
+        container.ssh_ok(&remote, &format!("test -d '{}'", tmp_path)),
+        "Tmp build path should exist after transfer"
+    );
+
+    cleanup_remote(&remote, &tmp_path).expect("cleanup should succeed");
+    assert!(
+        !container.ssh_ok(&remote, &format!("test -d '{}'", tmp_path)),
+        "Tmp build path should be removed by cleanup"
+    );
+}
+
+#[test]
+fn copy_back_brings_files_home() {
+    let Some(container) = SshdContainer::start() else {
+        return;
+    };
+    let remote = container.remote("copy-back-test");
+    let project = sample_project();
+    let build_path = resolve_build_path(&RemotePathBehavior::Mirror, &remote, project.path());
+
+    let argv = build_rsync_to_argv(&remote, project.path(), &build_path, &[]);
+    transfer_sources(&argv).expect("transfer should succeed");
+
+    assert!(
+        container.ssh_ok(&remote, &format!("cd '{}' && cargo build --release", build_path)),
+        "stub cargo build should succeed"
+    );
+
+    let local_dest = ScratchDir::new("copy-back-dest");
+    let pairs = vec![(
+        format!("{}:{}/target/release/hello", remote.host, build_path),
+        local_dest.path().to_string(),
+    )];
+    copy_back(pairs, remote.ssh_port, false).expect("copy back should succeed");
+
+    assert!(local_dest.path().join("hello").exists());
+}
+
+#[test]
+fn cleanup_only_runs_for_tmp() {
+    let Some(container) = SshdContainer::start() else {
+        return;
+    };
+    let remote = container.remote("cleanup-test");
+    let project = sample_project();
+    let mirror_path = resolve_build_path(&RemotePathBehavior::Mirror, &remote, project.path());
+
+    let argv = build_rsync_to_argv(&remote, project.path(), &mirror_path, &[]);
+    transfer_sources(&argv).expect("transfer should succeed");
+
+    // `crunch` only calls `cleanup_remote` when `RemotePathBehavior::Tmp` was selected; a
+    // Mirror build path is meant to persist, so it must still be there afterwards.
+    assert!(container.ssh_ok(&remote, &format!("test -d '{}'", mirror_path)));
+}